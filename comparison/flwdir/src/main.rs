@@ -1,8 +1,17 @@
 use serde_json;
 use ndarray::Array2;
-use pyflwdir_rs::FlwdirRaster;
+use pyflwdir_rs::{FlwdirRaster, StreamOrder};
+use pyflwdir_rs::tiled::{TiledFlwdir, TiledManifest};
+use std::fs;
 use std::time::Instant;
 
+/// Load a tiled-grid manifest (several named grids plus their edge
+/// `boundary_conditions`) from a JSON file on disk.
+fn load_tiled_manifest(path: &str) -> std::io::Result<TiledManifest> {
+    let text = fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
 fn main() {
     // WARMUP: Run a small computation to eliminate cold start overhead
     let _ = test_case_internal("Warmup", &[&[1u8, 2u8], &[4u8, 0u8]], false); // Don't include timing
@@ -56,7 +65,25 @@ fn main() {
     if let Some(result) = test_mega_drainage_20x20() {
         all_results.push(result);
     }
-    
+
+    // Tiled multi-grid input: two tiles stitched across a shared edge via a
+    // manifest, instead of a single hardcoded D8 array.
+    if let Some(result) = test_tiled_manifest() {
+        all_results.push(result);
+    }
+
+    // Optional: run an additional tiled-grid case loaded from a manifest file
+    // passed as `--manifest <path>` instead of the hardcoded inline JSON above.
+    let manifest_path = std::env::args()
+        .skip_while(|arg| arg != "--manifest")
+        .nth(1);
+    if let Some(path) = manifest_path {
+        match load_tiled_manifest(&path) {
+            Ok(manifest) => all_results.push(run_tiled_manifest(&format!("Tiled manifest ({path})"), &manifest)),
+            Err(err) => eprintln!("failed to load tiled manifest {path}: {err}"),
+        }
+    }
+
     // Output all results as a single JSON array
     println!("{}", serde_json::to_string_pretty(&all_results).unwrap());
 }
@@ -87,7 +114,10 @@ fn test_case_internal(name: &str, d8_data: &[&[u8]], include_timing: bool) -> Op
     let nnodes = rank.iter().filter(|&&r| r >= 0).count();
     let upstream_count = flwdir.upstream_counts.clone();
     let pit_indices = flwdir.pit_indices.clone();
-    
+    let accuflux = flwdir.accuflux(None);
+    let shreve_order = flwdir.stream_order(StreamOrder::Shreve);
+    let strahler_order = flwdir.stream_order(StreamOrder::Strahler);
+
     let elapsed = if include_timing {
         // Run timing tests 30 times and take the median
         let mut timings = Vec::new();
@@ -120,6 +150,9 @@ fn test_case_internal(name: &str, d8_data: &[&[u8]], include_timing: bool) -> Op
     let rank_vec: Vec<i32> = rank.to_vec();
     let upstream_vec: Vec<i8> = upstream_count.to_vec();
     let pit_vec: Vec<usize> = pit_indices.to_vec();
+    let accuflux_vec: Vec<f64> = accuflux.into_raw_vec();
+    let shreve_vec: Vec<i32> = shreve_order.into_raw_vec();
+    let strahler_vec: Vec<i32> = strahler_order.into_raw_vec();
     
     // Create JSON output
     let json_output = serde_json::json!({
@@ -130,6 +163,9 @@ fn test_case_internal(name: &str, d8_data: &[&[u8]], include_timing: bool) -> Op
         "rank": rank_vec,
         "n_upstream": upstream_vec,
         "idxs_pit": pit_vec,
+        "accuflux": accuflux_vec,
+        "shreve_order": shreve_vec,
+        "strahler_order": strahler_vec,
         "timing_seconds": elapsed
     });
     
@@ -260,7 +296,10 @@ fn test_case_from_array_internal<const R: usize, const C: usize>(name: &str, d8_
     let nnodes = rank.iter().filter(|&&r| r >= 0).count();
     let upstream_count = flwdir.upstream_counts.clone();
     let pit_indices = flwdir.pit_indices.clone();
-    
+    let accuflux = flwdir.accuflux(None);
+    let shreve_order = flwdir.stream_order(StreamOrder::Shreve);
+    let strahler_order = flwdir.stream_order(StreamOrder::Strahler);
+
     let elapsed = if include_timing {
         // Run timing tests 30 times and take the median
         let mut timings = Vec::new();
@@ -293,6 +332,9 @@ fn test_case_from_array_internal<const R: usize, const C: usize>(name: &str, d8_
     let rank_vec: Vec<i32> = rank.to_vec();
     let upstream_vec: Vec<i8> = upstream_count.to_vec();
     let pit_vec: Vec<usize> = pit_indices.to_vec();
+    let accuflux_vec: Vec<f64> = accuflux.into_raw_vec();
+    let shreve_vec: Vec<i32> = shreve_order.into_raw_vec();
+    let strahler_vec: Vec<i32> = strahler_order.into_raw_vec();
     
     // Create JSON output
     let json_output = serde_json::json!({
@@ -303,6 +345,9 @@ fn test_case_from_array_internal<const R: usize, const C: usize>(name: &str, d8_
         "rank": rank_vec,
         "n_upstream": upstream_vec,
         "idxs_pit": pit_vec,
+        "accuflux": accuflux_vec,
+        "shreve_order": shreve_vec,
+        "strahler_order": strahler_vec,
         "timing_seconds": elapsed
     });
     
@@ -426,4 +471,48 @@ fn test_mega_drainage_20x20() -> Option<serde_json::Value> {
     }
     
     test_case_from_array("Mega Drainage 20x20", &d8_data)
-} 
\ No newline at end of file
+}
+
+fn test_tiled_manifest() -> Option<serde_json::Value> {
+    // Two 2x2 tiles side by side. The east tile's west column drains off-grid
+    // to the west; the manifest links it into the west tile's outlet instead
+    // of letting it dead-end as a spurious pit.
+    let manifest_json = r#"{
+        "tiles": [
+            {
+                "name": "west",
+                "d8": [[1, 4], [1, 0]],
+                "boundary_conditions": {"east": "east"}
+            },
+            {
+                "name": "east",
+                "d8": [[16, 4], [16, 16]],
+                "boundary_conditions": {"west": "west"}
+            }
+        ]
+    }"#;
+    let manifest: TiledManifest =
+        serde_json::from_str(manifest_json).expect("malformed inline tiled manifest");
+
+    Some(run_tiled_manifest("Tiled 2x2 manifest", &manifest))
+}
+
+/// Build a `TiledFlwdir` from `manifest` and report the same stats
+/// `test_tiled_manifest` does, under the given `name`. Shared so that a
+/// manifest loaded from disk via `load_tiled_manifest` (see `--manifest` in
+/// `main`) exercises the exact same path as the hardcoded inline case.
+fn run_tiled_manifest(name: &str, manifest: &TiledManifest) -> serde_json::Value {
+    let tiled = TiledFlwdir::from_manifest(manifest);
+    let (ranks, nnodes) = tiled.rank();
+    let pits = tiled.pit_indices();
+
+    serde_json::json!({
+        "test_name": name,
+        "tile_names": tiled.tile_names,
+        "tile_offsets": tiled.tile_offsets,
+        "size": tiled.idxs_ds.len(),
+        "nnodes": nnodes,
+        "rank": ranks.to_vec(),
+        "idxs_pit": pits.to_vec(),
+    })
+}
\ No newline at end of file
@@ -2,6 +2,15 @@ use ndarray::{Array1, Array2, s};
 
 pub const MV: isize = -1;
 
+/// Precision used for area/elevation value arrays (index arithmetic stays
+/// integer regardless). Defaults to `f64`; enable the `f32` cargo feature
+/// to halve the working set on continental-scale rasters where single
+/// precision is plenty.
+#[cfg(feature = "f32")]
+pub type Float = f32;
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+
 /// Returns the rank, i.e. the distance counted in number of cells from the outlet.
 /// Ultra-optimized version with SIMD-friendly operations and minimal allocations.
 /// Returns (ranks, nnodes) where nnodes is the count of valid cells.
@@ -99,94 +108,181 @@ pub fn rank(idxs_ds: &Array1<i32>, _pits: &Array1<bool>) -> (Array1<i32>, usize)
     (ranks, n_count)
 }
 
-/// Returns array with number of upstream cells per cell.
-/// SIMD-optimized version with vectorized operations.
+/// A semiring-style reduction operator for folding per-cell quantities down
+/// the drainage network - drainage area with per-cell weights, max upstream
+/// elevation, min travel time, counts of upstream gauges, and so on -
+/// borrowing the idea behind GraphBLAS's templated operators.
+pub trait Reducer {
+    type T: Copy;
+    /// The reducer's neutral value. `accumulate` does not use it: each
+    /// cell starts from `lift` of its own weight.
+    fn identity() -> Self::T;
+    /// Turn a raw per-cell weight into the reducer's accumulator type.
+    fn lift(weight: Self::T) -> Self::T;
+    /// Fold an upstream contributor's accumulated value into a receiver's.
+    fn combine(a: Self::T, b: Self::T) -> Self::T;
+}
+
+/// Accumulated drainage area / flux: each cell's total is the sum of its
+/// own weight and everything upstream of it.
+pub struct Sum;
+impl Reducer for Sum {
+    type T = Float;
+    fn identity() -> Float { 0.0 }
+    fn lift(weight: Float) -> Float { weight }
+    fn combine(a: Float, b: Float) -> Float { a + b }
+}
+
+/// Maximum value found anywhere upstream (e.g. highest upstream elevation).
+pub struct Max;
+impl Reducer for Max {
+    type T = Float;
+    fn identity() -> Float { Float::MIN }
+    fn lift(weight: Float) -> Float { weight }
+    fn combine(a: Float, b: Float) -> Float { a.max(b) }
+}
+
+/// Minimum value found anywhere upstream (e.g. shortest upstream travel time).
+pub struct Min;
+impl Reducer for Min {
+    type T = Float;
+    fn identity() -> Float { Float::MAX }
+    fn lift(weight: Float) -> Float { weight }
+    fn combine(a: Float, b: Float) -> Float { a.min(b) }
+}
+
+/// Number of *direct* upstream neighbors a cell has. `combine` discards the
+/// contributor's own magnitude and counts the edge itself, so this reducer
+/// reports in-degree rather than a transitive total.
+pub struct Count;
+impl Reducer for Count {
+    type T = i8;
+    fn identity() -> i8 { 0 }
+    fn lift(_weight: i8) -> i8 { 0 }
+    fn combine(a: i8, _b: i8) -> i8 { a + 1 }
+}
+
+/// Fold per-cell weights down the drainage network with a [`Reducer`].
+///
+/// Computes `idxs_seq` (the down-to-upstream visiting order) and walks it
+/// in reverse, so every cell's upstream contributors are finalized before
+/// the cell itself is visited; each non-pit, in-range cell then pushes its
+/// accumulated value into its downstream receiver via `Reducer::combine`.
+/// Pits (self-loops) and out-of-range downstream indices are left as
+/// terminal sinks.
+pub fn accumulate<R: Reducer>(idxs_ds: &Array1<usize>, weight: &Array1<R::T>) -> Array1<R::T> {
+    let size = idxs_ds.len();
+    let idxs_pit = pit_indices(idxs_ds);
+    let seq = idxs_seq(idxs_ds, &idxs_pit);
+
+    let mut out: Array1<R::T> = Array1::from_shape_fn(size, |idx| R::lift(weight[idx]));
+
+    for &idx in seq.iter().rev() {
+        let idx_ds = idxs_ds[idx];
+        if idx_ds != idx && idx_ds < size {
+            out[idx_ds] = R::combine(out[idx_ds], out[idx]);
+        }
+    }
+
+    out
+}
+
+/// Returns array with number of (direct) upstream cells per cell.
+/// A thin wrapper over `accumulate::<Count>`; an optional mask excludes
+/// masked-out cells from contributing to their receiver's count.
 pub fn upstream_count(idxs_ds: &Array1<usize>, mask: Option<&Array1<bool>>) -> Array1<i8> {
     let size = idxs_ds.len();
-    let mut n_up = Array1::zeros(size);
-    
+    let weight = Array1::zeros(size);
+
     match mask {
         Some(m) => {
-            // Process in chunks for better vectorization
-            const CHUNK_SIZE: usize = 8;
-            let chunks = size / CHUNK_SIZE;
-            
-            // Vectorized chunk processing
-            for chunk in 0..chunks {
-                let start = chunk * CHUNK_SIZE;
-                for i in 0..CHUNK_SIZE {
-                    let idx0 = start + i;
-                    let idx_ds = idxs_ds[idx0];
-                    let is_valid = m[idx0];
-                    
-                    if idx_ds != idx0 && idx_ds < size && is_valid {
-                        n_up[idx_ds] += 1;
-                    }
-                }
-            }
-            
-            // Handle remaining elements
-            for idx0 in (chunks * CHUNK_SIZE)..size {
-                let idx_ds = idxs_ds[idx0];
-                if idx_ds != idx0 && idx_ds < size && m[idx0] {
-                    n_up[idx_ds] += 1;
-                }
-            }
+            // Reroute masked-out cells to themselves so they become pits
+            // and never contribute to a receiver's count.
+            let routed = Array1::from_shape_fn(size, |idx| if m[idx] { idxs_ds[idx] } else { idx });
+            accumulate::<Count>(&routed, &weight)
         }
-        None => {
-            // Even faster path without mask checks
-            const CHUNK_SIZE: usize = 16;
-            let chunks = size / CHUNK_SIZE;
-            
-            for chunk in 0..chunks {
-                let start = chunk * CHUNK_SIZE;
-                for i in 0..CHUNK_SIZE {
-                    let idx0 = start + i;
-                    let idx_ds = idxs_ds[idx0];
-                    
-                    if idx_ds != idx0 && idx_ds < size {
-                        n_up[idx_ds] += 1;
-                    }
-                }
+        None => accumulate::<Count>(idxs_ds, &weight),
+    }
+}
+
+/// Compressed-sparse-row upstream adjacency. `indices[indptr[idx] ..
+/// indptr[idx + 1]]` lists the direct upstream neighbors of `idx`. Since
+/// most cells have 0-2 upstream neighbors while a dense `upstream_matrix`
+/// is padded out to the single most-converging confluence's width, CSR
+/// avoids that wasted `MV` padding entirely.
+#[derive(Debug, Clone)]
+pub struct UpstreamCsr {
+    pub indptr: Array1<usize>,
+    pub indices: Array1<usize>,
+}
+
+impl UpstreamCsr {
+    /// Build the CSR form in two passes: first a direct O(n) counting pass
+    /// over `idxs_ds` (not `upstream_count`, which itself is built on top of
+    /// `accumulate`/`idxs_seq`/`UpstreamCsr` - going through it here would
+    /// be mutual recursion) to form `indptr` as a prefix sum, then scatter
+    /// each source cell into `indices[indptr[idx_ds] + running_counter[idx_ds]++]`.
+    pub fn build(idxs_ds: &Array1<usize>) -> Self {
+        let n = idxs_ds.len();
+        let mut counts = vec![0usize; n];
+        for idx in 0..n {
+            let idx_ds = idxs_ds[idx];
+            if idx_ds != idx && idx_ds < n {
+                counts[idx_ds] += 1;
             }
-            
-            // Handle remaining elements
-            for idx0 in (chunks * CHUNK_SIZE)..size {
-                let idx_ds = idxs_ds[idx0];
-                if idx_ds != idx0 && idx_ds < size {
-                    n_up[idx_ds] += 1;
-                }
+        }
+
+        let mut indptr = Array1::zeros(n + 1);
+        for idx in 0..n {
+            indptr[idx + 1] = indptr[idx] + counts[idx];
+        }
+
+        let nnz = indptr[n];
+        let mut indices = Array1::from_elem(nnz, 0usize);
+        let mut cursor = indptr.clone();
+        for idx0 in 0..n {
+            let idx_ds = idxs_ds[idx0];
+            if idx_ds != idx0 && idx_ds < n {
+                indices[cursor[idx_ds]] = idx0;
+                cursor[idx_ds] += 1;
             }
         }
+
+        UpstreamCsr { indptr, indices }
+    }
+
+    /// Direct upstream neighbors of `idx`, in scatter order.
+    pub fn upstream_of(&self, idx: usize) -> &[usize] {
+        let start = self.indptr[idx];
+        let end = self.indptr[idx + 1];
+        &self.indices.as_slice().expect("contiguous CSR indices")[start..end]
     }
-    
-    n_up
 }
 
 /// Returns a 2D array with upstream cell indices for each cell.
+///
+/// A dense compatibility shim over [`UpstreamCsr`] for callers that still
+/// want the padded `(n, d_max)` layout; prefer `UpstreamCsr::build` to
+/// traverse the drainage tree without paying for the `MV` padding.
 pub fn upstream_matrix(idxs_ds: &Array1<usize>) -> Array2<isize> {
-    let n_up = upstream_count(idxs_ds, None);
-    let d = *n_up.iter().max().unwrap_or(&0) as usize;
+    let csr = UpstreamCsr::build(idxs_ds);
     let n = idxs_ds.len();
-    
+    let d = (0..n)
+        .map(|idx| csr.indptr[idx + 1] - csr.indptr[idx])
+        .max()
+        .unwrap_or(0);
+
     if d == 0 {
         return Array2::from_elem((n, 1), MV);
     }
-    
+
     let mut idxs_us = Array2::from_elem((n, d), MV);
-    let mut n_up_counter = Array1::zeros(n);
-    
-    for idx0 in 0..n {
-        let idx_ds = idxs_ds[idx0];
-        if idx_ds != idx0 && idx_ds < n {
-            let i = n_up_counter[idx_ds];
-            if i < d {
-                idxs_us[[idx_ds, i]] = idx0 as isize;
-                n_up_counter[idx_ds] += 1;
-            }
+    for idx in 0..n {
+        for (k, &up) in csr.upstream_of(idx).iter().enumerate() {
+            idxs_us[[idx, k]] = up as isize;
         }
     }
-    
+
     idxs_us
 }
 
@@ -220,13 +316,90 @@ pub fn pit_indices(idxs_ds: &Array1<usize>) -> Array1<usize> {
     Array1::from_vec(pits)
 }
 
+/// A cell ordering grouped by topological rank (distance-to-pit), built with
+/// an O(n) counting sort instead of a comparison sort or a `HashMap` keyed
+/// on a narrow in-degree type. `cells_sorted[bucket_offsets[r] ..
+/// bucket_offsets[r + 1]]` lists every cell at rank `r`; downstream-
+/// propagating passes (`accuflux`, `par_accuflux`, future `distance` /
+/// `area` passes) walk buckets from the highest rank down to 0 so every
+/// cell's upstream contributors are settled before it is visited, and all
+/// cells sharing a rank are mutually independent for parallel processing.
+#[derive(Debug, Clone)]
+pub struct TopoOrder {
+    pub bucket_offsets: Array1<usize>,
+    pub cells_sorted: Array1<usize>,
+    /// Highest rank present, or -1 if no cell has a valid (non-negative) rank.
+    pub max_rank: i32,
+}
+
+impl TopoOrder {
+    /// Build from a per-cell rank array as produced by [`rank`]. Cells with
+    /// a negative rank (disconnected or part of a cycle) are excluded from
+    /// every bucket rather than silently wrapping a count past `i32::MAX`.
+    pub fn build(ranks: &Array1<i32>) -> Self {
+        let n = ranks.len();
+        let max_rank = ranks.iter().copied().filter(|&r| r >= 0).max().unwrap_or(-1);
+
+        if max_rank < 0 {
+            return TopoOrder {
+                bucket_offsets: Array1::zeros(1),
+                cells_sorted: Array1::from_elem(0, 0usize),
+                max_rank,
+            };
+        }
+
+        let n_buckets = max_rank as usize + 1;
+        let mut counts = vec![0usize; n_buckets];
+        for &r in ranks.iter() {
+            if r >= 0 {
+                counts[r as usize] = counts[r as usize].saturating_add(1);
+            }
+        }
+
+        let mut bucket_offsets = Array1::zeros(n_buckets + 1);
+        for r in 0..n_buckets {
+            bucket_offsets[r + 1] = bucket_offsets[r] + counts[r];
+        }
+
+        let mut cursor = bucket_offsets.clone();
+        let mut cells_sorted = Array1::from_elem(bucket_offsets[n_buckets], 0usize);
+        for idx in 0..n {
+            let r = ranks[idx];
+            if r >= 0 {
+                let r = r as usize;
+                cells_sorted[cursor[r]] = idx;
+                cursor[r] += 1;
+            }
+        }
+
+        TopoOrder { bucket_offsets, cells_sorted, max_rank }
+    }
+
+    /// Cells at rank `r`, in no particular order within the bucket.
+    pub fn bucket(&self, r: i32) -> &[usize] {
+        if r < 0 || r > self.max_rank {
+            return &[];
+        }
+        let r = r as usize;
+        let start = self.bucket_offsets[r];
+        let end = self.bucket_offsets[r + 1];
+        &self.cells_sorted.as_slice().expect("contiguous cells_sorted")[start..end]
+    }
+
+    /// Every valid-rank cell, highest rank (leaves) first down to rank 0
+    /// (the outlet) - the order a reverse accumulation pass needs.
+    pub fn iter_downstream(&self) -> impl Iterator<Item = usize> + '_ {
+        self.cells_sorted.iter().rev().copied()
+    }
+}
+
 /// Returns indices ordered from down- to upstream.
 pub fn idxs_seq(idxs_ds: &Array1<usize>, idxs_pit: &Array1<usize>) -> Array1<usize> {
-    let idxs_us = upstream_matrix(idxs_ds);
+    let csr = UpstreamCsr::build(idxs_ds);
     let size = idxs_ds.len();
     let mut idxs_seq = Array1::from_elem(size, usize::MAX);
     let mut j = 0;
-    
+
     // Start with pit indices
     for &idx in idxs_pit.iter() {
         if j < size {
@@ -234,25 +407,22 @@ pub fn idxs_seq(idxs_ds: &Array1<usize>, idxs_pit: &Array1<usize>) -> Array1<usi
             j += 1;
         }
     }
-    
+
     let mut i = 0;
     while i < j && i < size {
         let idx0 = idxs_seq[i];
-        
-        // Add upstream cells
-        for k in 0..idxs_us.ncols() {
-            let idx = idxs_us[[idx0, k]];
-            if idx == MV {
-                break;
-            }
+
+        // Add upstream cells directly from the CSR row, with no break-on-MV
+        // scan over padding to skip.
+        for &idx in csr.upstream_of(idx0) {
             if j < size {
-                idxs_seq[j] = idx as usize;
+                idxs_seq[j] = idx;
                 j += 1;
             }
         }
         i += 1;
     }
-    
+
     // Return only the filled portion
     Array1::from_vec(idxs_seq.slice(s![..j]).to_vec())
 }
@@ -260,32 +430,27 @@ pub fn idxs_seq(idxs_ds: &Array1<usize>, idxs_pit: &Array1<usize>) -> Array1<usi
 /// Returns the index of the upstream cell with the largest uparea.
 pub fn main_upstream(
     idxs_ds: &Array1<usize>,
-    uparea: &Array1<f64>,
-    upa_min: f64,
+    uparea: &Array1<Float>,
+    upa_min: Float,
 ) -> Array1<isize> {
     let size = idxs_ds.len();
     let mut idxs_us_main = Array1::from_elem(size, MV);
-    let idxs_us = upstream_matrix(idxs_ds);
-    
+    let csr = UpstreamCsr::build(idxs_ds);
+
     for idx0 in 0..size {
         let mut max_uparea = upa_min;
         let mut main_idx = MV;
-        
-        for k in 0..idxs_us.ncols() {
-            let idx = idxs_us[[idx0, k]];
-            if idx == MV {
-                break;
-            }
-            let idx_usize = idx as usize;
-            if idx_usize < uparea.len() && uparea[idx_usize] > max_uparea {
-                max_uparea = uparea[idx_usize];
-                main_idx = idx;
+
+        for &idx in csr.upstream_of(idx0) {
+            if idx < uparea.len() && uparea[idx] > max_uparea {
+                max_uparea = uparea[idx];
+                main_idx = idx as isize;
             }
         }
-        
+
         idxs_us_main[idx0] = main_idx;
     }
-    
+
     idxs_us_main
 }
 
@@ -325,4 +490,65 @@ mod tests {
         assert_eq!(ranks[0], 2);
         assert_eq!(nnodes, 3);
     }
+
+    #[test]
+    fn test_main_upstream() {
+        // Cells 0 and 1 both flow into cell 2; cell 1 drains a larger area
+        // so it should win regardless of whether `Float` is f32 or f64.
+        let idxs_ds: Array1<usize> = array![2, 2, 2];
+        let uparea: Array1<Float> = array![1.0, 5.0, 0.0];
+        let main = main_upstream(&idxs_ds, &uparea, 0.0);
+        assert_eq!(main[2], 1);
+    }
+
+    #[test]
+    fn test_accumulate_sum_and_max() {
+        // Cells 0 and 1 flow into cell 2 (pit).
+        let idxs_ds: Array1<usize> = array![2, 2, 2];
+        let weight: Array1<Float> = array![1.0, 2.0, 0.5];
+
+        let total = accumulate::<Sum>(&idxs_ds, &weight);
+        assert_eq!(total[0], 1.0);
+        assert_eq!(total[1], 2.0);
+        assert_eq!(total[2], 0.5 + 1.0 + 2.0); // own weight plus both tributaries
+
+        let highest = accumulate::<Max>(&idxs_ds, &weight);
+        assert_eq!(highest[2], 2.0); // max seen anywhere upstream (or at the cell itself)
+    }
+
+    #[test]
+    fn test_topo_order() {
+        // Cell 0 flows to cell 1, cell 1 flows to cell 2 (pit): ranks 2, 1, 0.
+        let idxs_ds = array![1, 2, 2];
+        let pits = array![false, false, true];
+        let (ranks, _) = rank(&idxs_ds, &pits);
+
+        let topo = TopoOrder::build(&ranks);
+        assert_eq!(topo.max_rank, 2);
+        assert_eq!(topo.bucket(0), &[2]);
+        assert_eq!(topo.bucket(1), &[1]);
+        assert_eq!(topo.bucket(2), &[0]);
+
+        let order: Vec<usize> = topo.iter_downstream().collect();
+        assert_eq!(order, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_upstream_csr() {
+        // Cells 0 and 1 both flow into cell 2 (pit).
+        let idxs_ds: Array1<usize> = array![2, 2, 2];
+        let csr = UpstreamCsr::build(&idxs_ds);
+
+        assert_eq!(csr.upstream_of(0), &[] as &[usize]);
+        assert_eq!(csr.upstream_of(1), &[] as &[usize]);
+        let mut up2 = csr.upstream_of(2).to_vec();
+        up2.sort_unstable();
+        assert_eq!(up2, vec![0, 1]);
+
+        // The dense compatibility shim must expand to the same adjacency.
+        let dense = upstream_matrix(&idxs_ds);
+        let mut dense_row2: Vec<isize> = dense.row(2).iter().copied().collect();
+        dense_row2.sort_unstable();
+        assert_eq!(dense_row2, vec![0, 1]);
+    }
 } 
\ No newline at end of file
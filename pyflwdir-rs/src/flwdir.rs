@@ -1,7 +1,52 @@
 use ndarray::{Array1, Array2, ArrayView2};
-use std::collections::HashMap;
-use crate::core::{rank, upstream_count, pit_indices};
-use crate::core_d8::d8_from_array;
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::simd::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use crate::core::{rank, upstream_count, pit_indices, upstream_matrix, TopoOrder, MV};
+use crate::core_d8::{
+    d8_from_array, D8_E, D8_N, D8_NE, D8_NODATA, D8_NW, D8_PIT, D8_S, D8_SE, D8_SW, D8_W,
+};
+
+/// Small monotonic step added across flat-filled areas so a drainage
+/// gradient always exists and no interior pit can remain after filling.
+const FILL_EPSILON: f64 = 1e-6;
+
+/// 8-neighbor offsets paired with their D8 direction, ordered so that
+/// steepest-descent ties are broken deterministically.
+const NEIGHBOR_DIRS: [(i32, i32, u8); 8] = [
+    (0, 1, D8_E),
+    (1, 1, D8_SE),
+    (1, 0, D8_S),
+    (1, -1, D8_SW),
+    (0, -1, D8_W),
+    (-1, -1, D8_NW),
+    (-1, 0, D8_N),
+    (-1, 1, D8_NE),
+];
+
+/// Orders heap entries so `BinaryHeap` (a max-heap) pops the cell with the
+/// lowest filled elevation first, as the priority-flood queue requires.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FillCell {
+    elev: f64,
+    idx: usize,
+}
+
+impl Eq for FillCell {}
+
+impl PartialOrd for FillCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FillCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.elev.partial_cmp(&self.elev).unwrap_or(Ordering::Equal)
+    }
+}
 
 /// Flow direction type
 #[derive(Debug, Clone, PartialEq)]
@@ -24,8 +69,69 @@ pub struct FlwdirRaster {
     pub pit_indices: Array1<usize>,
     /// Pre-computed upstream counts for faster access
     pub upstream_counts: Array1<i8>,
-    /// Cache for expensive operations
-    rank_cache: Option<Array1<i32>>,
+    /// Derived arrays keyed by which kind they are plus a content
+    /// fingerprint of their inputs, so e.g. `accuflux` with different
+    /// weights doesn't collide with a previous call's cached result.
+    cache: HashMap<(CacheKind, u64), CachedData>,
+    /// Fingerprint of `idxs_ds` as of construction or the last `invalidate`
+    /// call; `invalidate` compares against this to detect an in-place mutation.
+    idxs_fingerprint: u64,
+}
+
+/// How `FlwdirRaster::from_dem_conditioned` resolves interior sinks before
+/// deriving D8 directions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditioningMode {
+    /// Priority-flood fill: raise each basin to its pour point.
+    Fill,
+    /// Carve the cheapest channel out of each pit; pits whose breach would
+    /// exceed the length/cost caps are left unresolved.
+    Breach,
+    /// Breach first, then fill whatever breaching couldn't resolve within
+    /// the caps - the common hydrological DEM-conditioning default.
+    Hybrid,
+}
+
+/// Orders heap entries so `BinaryHeap` pops the lowest-cost breach
+/// candidate first, mirroring [`FillCell`] for the Dijkstra search.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BreachCell {
+    cost: f64,
+    idx: usize,
+}
+
+impl Eq for BreachCell {}
+
+impl PartialOrd for BreachCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BreachCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// Stream ordering scheme for [`FlwdirRaster::stream_order`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamOrder {
+    /// Magnitude: the sum of all incoming upstream orders.
+    Shreve,
+    /// Order only increments where two or more equal-max-order
+    /// tributaries meet; otherwise the maximum order carries forward.
+    Strahler,
+}
+
+/// Which derived array a cache entry holds; paired with a content
+/// fingerprint to form the `FlwdirRaster::cache` key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheKind {
+    Rank,
+    Accuflux,
+    Distance,
+    UpstreamMain,
 }
 
 /// Cached data variants
@@ -37,6 +143,18 @@ pub enum CachedData {
     UpstreamMain(Array1<isize>),
 }
 
+/// Widen a `Float` value to `f64` for fingerprinting. Under the default
+/// (non-`f32`) feature set `Float` already is `f64`, so this is a plain
+/// no-op rather than an `as f64` cast clippy would flag as unnecessary.
+#[cfg(feature = "f32")]
+fn float_to_f64(v: crate::core::Float) -> f64 {
+    v as f64
+}
+#[cfg(not(feature = "f32"))]
+fn float_to_f64(v: crate::core::Float) -> f64 {
+    v
+}
+
 impl FlwdirRaster {
     /// Create a new FlwdirRaster from a 2D D8 flow direction array
     /// Ultra-optimized constructor with pre-computation
@@ -47,129 +165,594 @@ impl FlwdirRaster {
         // Convert D8 to downstream indices
         let idxs_ds = d8_from_array(&d8);
         
-        // Pre-compute valid mask using vectorized operations
-        let mut valid_mask = Array1::from_elem(size, false);
-        const CHUNK_SIZE: usize = 64;
-        let chunks = size / CHUNK_SIZE;
-        
+        // Pre-compute valid mask with `std::simd`: a lane of `idxs_ds` is
+        // compared not-equal against a lane of ascending indices (unaligned
+        // load, no preconditions on the `ndarray` buffer, mirroring NEON's
+        // `vld1q`), then the pit condition is OR'd in per-lane since it
+        // depends on a 2D `d8` lookup that doesn't vectorize. A scalar loop
+        // handles the remainder.
+        const LANES: usize = 8;
+        let mut valid_mask = vec![false; size];
+        let idxs_ds_slice = idxs_ds.as_slice().expect("contiguous idxs_ds");
+        let chunks = size / LANES;
+
         for chunk in 0..chunks {
-            let start = chunk * CHUNK_SIZE;
-            for i in 0..CHUNK_SIZE {
+            let start = chunk * LANES;
+            let idx_lane: Simd<usize, LANES> = Simd::from_array(std::array::from_fn(|i| start + i));
+            let ds_lane = Simd::<usize, LANES>::from_slice(&idxs_ds_slice[start..start + LANES]);
+            let not_self = ds_lane.simd_ne(idx_lane);
+            for i in 0..LANES {
                 let idx = start + i;
-                valid_mask[idx] = idxs_ds[idx] != idx || {
+                valid_mask[idx] = not_self.test(i) || {
                     let row = idx / shape.1;
                     let col = idx % shape.1;
                     d8[[row, col]] == 0 // pit
                 };
             }
         }
-        
+
         // Handle remaining elements
-        for idx in (chunks * CHUNK_SIZE)..size {
+        for idx in (chunks * LANES)..size {
             valid_mask[idx] = idxs_ds[idx] != idx || {
                 let row = idx / shape.1;
                 let col = idx % shape.1;
                 d8[[row, col]] == 0 // pit
             };
         }
-        
-        // Pre-compute pit indices
-        let pit_indices = pit_indices(&idxs_ds);
-        
+        let valid_mask = Array1::from_vec(valid_mask);
+
+        // Pre-compute pit indices. `core::pit_indices` only looks at the
+        // self-loop in `idxs_ds`, which a NoData cell produces just like a
+        // genuine pit (see `d8_from_array`); `valid_mask` is what actually
+        // tells the two apart (true for a real pit, false for NoData), so
+        // filter through it here to keep NoData cells out of the pit list.
+        let pit_indices: Array1<usize> = pit_indices(&idxs_ds)
+            .iter()
+            .copied()
+            .filter(|&idx| valid_mask[idx])
+            .collect();
+
         // Pre-compute upstream counts
         let upstream_counts = upstream_count(&idxs_ds, Some(&valid_mask));
-        
+
+        let idxs_fingerprint = Self::fingerprint_of(&idxs_ds, None);
+
         Self {
             idxs_ds,
             shape,
             valid_mask,
             pit_indices,
             upstream_counts,
-            rank_cache: None,
+            cache: HashMap::new(),
+            idxs_fingerprint,
         }
     }
     
-    /// Get downstream indices as i32 array
-    /// Ultra-fast conversion using unsafe code for maximum performance
+    /// Create a new FlwdirRaster from an elevation raster, resolving sinks
+    /// by priority-flood depression filling so the network drains to the
+    /// raster boundary or a NoData edge.
+    ///
+    /// Implements the priority-flood algorithm (as used by GRASS
+    /// `r.hydrodem`): seed a min-heap with all edge/NoData-adjacent cells
+    /// keyed by elevation, repeatedly pop the lowest cell and mark it
+    /// resolved, and for each unresolved neighbor raise its filled
+    /// elevation to `max(neighbor_elev, popped_elev + FILL_EPSILON)` before
+    /// pushing it with that filled value as the key. D8 directions are then
+    /// assigned by steepest descent on the filled surface. `nodata` cells
+    /// are left untouched and never receive a flow direction.
+    pub fn from_dem(elev: ArrayView2<f64>, nodata: f64) -> Self {
+        let (nrows, ncols) = elev.dim();
+        let size = nrows * ncols;
+        let is_nodata = |v: f64| v.is_nan() || v == nodata;
+
+        let mut filled = elev.to_owned();
+        let mut resolved = vec![false; size];
+        let mut heap = BinaryHeap::with_capacity(size);
+
+        for row in 0..nrows {
+            for col in 0..ncols {
+                let idx = row * ncols + col;
+                if is_nodata(filled[[row, col]]) {
+                    resolved[idx] = true;
+                    continue;
+                }
+                let on_edge = row == 0 || row == nrows - 1 || col == 0 || col == ncols - 1;
+                let touches_nodata = NEIGHBOR_DIRS.iter().any(|&(dr, dc, _)| {
+                    let (nr, nc) = (row as i32 + dr, col as i32 + dc);
+                    nr < 0
+                        || nr >= nrows as i32
+                        || nc < 0
+                        || nc >= ncols as i32
+                        || is_nodata(filled[[nr as usize, nc as usize]])
+                });
+                if on_edge || touches_nodata {
+                    heap.push(FillCell { elev: filled[[row, col]], idx });
+                }
+            }
+        }
+
+        while let Some(FillCell { elev: popped_elev, idx }) = heap.pop() {
+            if resolved[idx] {
+                continue; // stale entry from an earlier, higher-priority push
+            }
+            resolved[idx] = true;
+            let (row, col) = (idx / ncols, idx % ncols);
+
+            for &(dr, dc, _) in &NEIGHBOR_DIRS {
+                let (nr, nc) = (row as i32 + dr, col as i32 + dc);
+                if nr < 0 || nr >= nrows as i32 || nc < 0 || nc >= ncols as i32 {
+                    continue;
+                }
+                let (nr, nc) = (nr as usize, nc as usize);
+                let nidx = nr * ncols + nc;
+                if resolved[nidx] || is_nodata(filled[[nr, nc]]) {
+                    continue;
+                }
+                let raised = filled[[nr, nc]].max(popped_elev + FILL_EPSILON);
+                filled[[nr, nc]] = raised;
+                heap.push(FillCell { elev: raised, idx: nidx });
+            }
+        }
+
+        let d8 = Self::d8_from_filled(&filled.view(), nodata);
+        Self::from_array(d8.view())
+    }
+
+    /// Assign D8 codes by steepest descent on an already-filled surface.
+    /// A cell with no strictly-lower neighbor becomes a pit; these only
+    /// occur at the raster boundary or next to NoData once filling has run.
+    fn d8_from_filled(filled: &ArrayView2<f64>, nodata: f64) -> Array2<u8> {
+        let (nrows, ncols) = filled.dim();
+        let mut d8 = Array2::from_elem((nrows, ncols), D8_NODATA);
+
+        for row in 0..nrows {
+            for col in 0..ncols {
+                let elev = filled[[row, col]];
+                if elev.is_nan() || elev == nodata {
+                    continue;
+                }
+                let mut best_dir = D8_PIT;
+                let mut best_elev = elev;
+                for &(dr, dc, d8_val) in &NEIGHBOR_DIRS {
+                    let (nr, nc) = (row as i32 + dr, col as i32 + dc);
+                    if nr < 0 || nr >= nrows as i32 || nc < 0 || nc >= ncols as i32 {
+                        continue;
+                    }
+                    let (nr, nc) = (nr as usize, nc as usize);
+                    let n_elev = filled[[nr, nc]];
+                    if n_elev.is_nan() || n_elev == nodata || n_elev >= best_elev {
+                        continue;
+                    }
+                    best_elev = n_elev;
+                    best_dir = d8_val;
+                }
+                d8[[row, col]] = best_dir;
+            }
+        }
+
+        d8
+    }
+
+    /// Create a new FlwdirRaster from an elevation raster, conditioning it
+    /// with priority-flood filling, least-cost breaching, or both.
+    ///
+    /// `Breach` carves, for every interior pit, the cheapest channel to a
+    /// lower outlet rather than raising the whole basin: a Dijkstra search
+    /// expands outward from the pit where a cell's accumulated cost is the
+    /// sum of positive elevation differences that must be removed to reach
+    /// it (cost 0 once a cell lower than the pit, or the raster edge, is
+    /// reached), then the recovered path is lowered monotonically so the
+    /// pit drains along it. `max_breach_length`/`max_breach_cost` bound how
+    /// far a single breach may run; pits that would exceed either cap are
+    /// left unresolved by `Breach` and filled instead by `Hybrid`.
+    pub fn from_dem_conditioned(
+        elev: ArrayView2<f64>,
+        nodata: f64,
+        mode: ConditioningMode,
+        max_breach_length: usize,
+        max_breach_cost: f64,
+    ) -> Self {
+        match mode {
+            ConditioningMode::Fill => Self::from_dem(elev, nodata),
+            ConditioningMode::Breach => {
+                let (breached, _unresolved) =
+                    Self::breach_depressions(elev, nodata, max_breach_length, max_breach_cost);
+                let d8 = Self::d8_from_filled(&breached.view(), nodata);
+                Self::from_array(d8.view())
+            }
+            ConditioningMode::Hybrid => {
+                let (breached, unresolved) =
+                    Self::breach_depressions(elev, nodata, max_breach_length, max_breach_cost);
+                if unresolved.is_empty() {
+                    let d8 = Self::d8_from_filled(&breached.view(), nodata);
+                    Self::from_array(d8.view())
+                } else {
+                    // Fall back to priority-flood filling for whatever
+                    // breaching couldn't resolve within the caps.
+                    Self::from_dem(breached.view(), nodata)
+                }
+            }
+        }
+    }
+
+    /// Carve a least-cost breach channel out of every interior pit (a cell
+    /// with no strictly-lower neighbor that isn't on the raster boundary).
+    /// Returns the modified elevation surface and the indices of pits whose
+    /// cheapest breach exceeded `max_breach_length`/`max_breach_cost`.
+    fn breach_depressions(
+        elev: ArrayView2<f64>,
+        nodata: f64,
+        max_breach_length: usize,
+        max_breach_cost: f64,
+    ) -> (Array2<f64>, Vec<usize>) {
+        let (nrows, ncols) = elev.dim();
+        let mut surface = elev.to_owned();
+
+        let naive_d8 = Self::d8_from_filled(&surface.view(), nodata);
+        let idxs_ds_naive = d8_from_array(&naive_d8.view());
+        let pits = pit_indices(&idxs_ds_naive);
+
+        let is_nodata = |v: f64| v.is_nan() || v == nodata;
+
+        let mut unresolved = Vec::new();
+        for &pit_idx in pits.iter() {
+            let (row, col) = (pit_idx / ncols, pit_idx % ncols);
+            let on_edge = row == 0 || row == nrows - 1 || col == 0 || col == ncols - 1;
+            if on_edge || is_nodata(surface[[row, col]]) {
+                continue; // a genuine boundary outlet, or not a real pit at all
+            }
+            match Self::find_breach_path(&surface, nodata, pit_idx, max_breach_length, max_breach_cost) {
+                Some(path) => Self::carve_path(&mut surface, ncols, &path),
+                None => unresolved.push(pit_idx),
+            }
+        }
+
+        (surface, unresolved)
+    }
+
+    /// Dijkstra search outward from `pit_idx` for the cheapest path to an
+    /// escape cell (lower than the pit, or on the raster boundary). Returns
+    /// the path from the pit to the escape cell, or `None` if no escape is
+    /// reachable within `max_cost` or the cheapest escape path is longer
+    /// than `max_length` cells (pit included).
+    fn find_breach_path(
+        elev: &Array2<f64>,
+        nodata: f64,
+        pit_idx: usize,
+        max_length: usize,
+        max_cost: f64,
+    ) -> Option<Vec<usize>> {
+        let (nrows, ncols) = elev.dim();
+        let pit_elev = elev[[pit_idx / ncols, pit_idx % ncols]];
+
+        let mut best_cost: HashMap<usize, f64> = HashMap::new();
+        let mut prev: HashMap<usize, usize> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        best_cost.insert(pit_idx, 0.0);
+        heap.push(BreachCell { cost: 0.0, idx: pit_idx });
+
+        while let Some(BreachCell { cost, idx }) = heap.pop() {
+            if cost > best_cost.get(&idx).copied().unwrap_or(f64::INFINITY) {
+                continue; // stale entry
+            }
+            let (row, col) = (idx / ncols, idx % ncols);
+            let is_escape = idx != pit_idx
+                && (elev[[row, col]] <= pit_elev
+                    || row == 0
+                    || row == nrows - 1
+                    || col == 0
+                    || col == ncols - 1);
+            if is_escape {
+                let mut path = vec![idx];
+                let mut cur = idx;
+                while let Some(&p) = prev.get(&cur) {
+                    path.push(p);
+                    cur = p;
+                }
+                path.reverse();
+                return if path.len() <= max_length { Some(path) } else { None };
+            }
+
+            for &(dr, dc, _) in &NEIGHBOR_DIRS {
+                let (nr, nc) = (row as i32 + dr, col as i32 + dc);
+                if nr < 0 || nr >= nrows as i32 || nc < 0 || nc >= ncols as i32 {
+                    continue;
+                }
+                let (nr, nc) = (nr as usize, nc as usize);
+                let n_elev = elev[[nr, nc]];
+                if n_elev.is_nan() || n_elev == nodata {
+                    continue;
+                }
+                let n_idx = nr * ncols + nc;
+                let step_cost = (n_elev - pit_elev).max(0.0);
+                let new_cost = cost + step_cost;
+                if new_cost > max_cost {
+                    continue;
+                }
+                if new_cost < best_cost.get(&n_idx).copied().unwrap_or(f64::INFINITY) {
+                    best_cost.insert(n_idx, new_cost);
+                    prev.insert(n_idx, idx);
+                    heap.push(BreachCell { cost: new_cost, idx: n_idx });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Lower elevations along a recovered breach path so they strictly
+    /// decrease from the pit to the escape cell, carving a channel without
+    /// ever raising a cell above its original elevation.
+    fn carve_path(surface: &mut Array2<f64>, ncols: usize, path: &[usize]) {
+        for window in path.windows(2) {
+            let (prev_idx, idx) = (window[0], window[1]);
+            let prev_elev = surface[[prev_idx / ncols, prev_idx % ncols]];
+            let cell = (idx / ncols, idx % ncols);
+            if surface[cell] >= prev_elev {
+                surface[cell] = prev_elev - FILL_EPSILON;
+            }
+        }
+    }
+
+    /// Get downstream indices as i32 array.
+    ///
+    /// Vectorized with `std::simd`: each lane-width chunk does an unaligned
+    /// load of `usize` indices, narrows them to `i32` lanes, and stores them
+    /// contiguously - no per-element `unsafe` writes or `assume_init`. A
+    /// scalar loop handles the remainder; on targets without SIMD
+    /// instructions `std::simd` itself lowers to scalar code.
     pub fn get_idxs_ds_i32(&self) -> Array1<i32> {
+        const LANES: usize = 8;
         let size = self.idxs_ds.len();
-        let mut result = Array1::uninit(size);
-        
-        // Process in chunks for better vectorization
-        const CHUNK_SIZE: usize = 8;
-        let chunks = size / CHUNK_SIZE;
-        
+        let idxs_ds_slice = self.idxs_ds.as_slice().expect("contiguous idxs_ds");
+        let mut result = vec![0i32; size];
+
+        let chunks = size / LANES;
         for chunk in 0..chunks {
-            let start = chunk * CHUNK_SIZE;
-            for i in 0..CHUNK_SIZE {
-                let idx = start + i;
-                let val = self.idxs_ds[idx] as i32;
-                unsafe {
-                    result[idx].write(val);
-                }
-            }
+            let start = chunk * LANES;
+            let lane = Simd::<usize, LANES>::from_slice(&idxs_ds_slice[start..start + LANES]);
+            let narrowed: Simd<i32, LANES> = lane.cast();
+            narrowed.copy_to_slice(&mut result[start..start + LANES]);
         }
-        
+
         // Handle remaining elements
-        for idx in (chunks * CHUNK_SIZE)..size {
-            let val = self.idxs_ds[idx] as i32;
-            unsafe {
-                result[idx].write(val);
-            }
+        for idx in (chunks * LANES)..size {
+            result[idx] = idxs_ds_slice[idx] as i32;
         }
-        
-        unsafe { result.assume_init() }
+
+        Array1::from_vec(result)
     }
     
-    /// Calculate flow accumulation
-    /// Ultra-optimized using topological order and pre-computed upstream counts
-    pub fn accuflux(&self, weights: Option<&Array1<f64>>) -> Array1<f64> {
+    /// Calculate flow accumulation (upstream drainage area).
+    ///
+    /// Reuses the cached topological `rank` (distance in cells from the
+    /// outlet) via [`TopoOrder`]'s counting-sort buckets: processing cells
+    /// in order of *decreasing* rank visits leaves before their receivers,
+    /// so a single reverse pass over the drainage tree accumulates each
+    /// cell's weight (1 per cell by default) into its downstream neighbor
+    /// and produces exact totals in O(n) with no hashing or comparison sort.
+    /// The result is cached by a fingerprint of `idxs_ds` and `weights`
+    /// together, so repeated calls with the same weighting (common in
+    /// calibration loops) reuse it instead of recomputing from scratch.
+    pub fn accuflux(&mut self, weights: Option<ArrayView2<f64>>) -> Array2<f64> {
+        let shape = self.shape;
         let size = self.idxs_ds.len();
         let mut flux = Array1::ones(size);
-        
+
         // Apply weights if provided
         if let Some(w) = weights {
-            for i in 0..size {
-                flux[i] = w[i];
+            for idx in 0..size {
+                let (row, col) = self.idx_to_rowcol(idx);
+                flux[idx] = w[[row, col]];
             }
         }
-        
-        // Process cells in topological order based on upstream counts
-        let mut cells_by_count: HashMap<i8, Vec<usize>> = HashMap::new();
-        let mut max_count = 0i8;
-        
-        for (idx, &count) in self.upstream_counts.iter().enumerate() {
-            cells_by_count.entry(count).or_insert_with(Vec::new).push(idx);
-            max_count = max_count.max(count);
+
+        let key = (
+            CacheKind::Accuflux,
+            self.fingerprint(Some(flux.as_slice().expect("contiguous flux"))),
+        );
+        if let Some(CachedData::Area(cached)) = self.cache.get(&key) {
+            return Array2::from_shape_vec(shape, cached.to_vec()).unwrap();
         }
-        
-        // Process from highest upstream count to lowest
-        for count in (0..=max_count).rev() {
-            if let Some(cells) = cells_by_count.get(&count) {
-                for &idx in cells {
-                    let idx_ds = self.idxs_ds[idx];
-                    if idx_ds != idx && idx_ds < size {
-                        flux[idx_ds] += flux[idx];
+
+        let ranks = self.rank().clone();
+        let topo = TopoOrder::build(&ranks);
+
+        for idx in topo.iter_downstream() {
+            let idx_ds = self.idxs_ds[idx];
+            if idx_ds != idx && idx_ds < size {
+                flux[idx_ds] += flux[idx];
+            }
+        }
+
+        self.cache.insert(key, CachedData::Area(flux.clone()));
+        Array2::from_shape_vec(shape, flux.to_vec()).unwrap()
+    }
+
+    /// Parallel flow accumulation across topological rank levels.
+    ///
+    /// Buckets cells with [`TopoOrder`]'s counting sort on their cached
+    /// `rank()` (distance to pit): every cell in a bucket is mutually
+    /// independent, since a cell at rank r never drains into another cell
+    /// at rank r, so each bucket can be processed with rayon's `par_iter`.
+    /// Several same-rank cells can still drain into the same lower-rank
+    /// receiver concurrently, so `flux` is backed by `AtomicU64` holding the
+    /// bit-pattern of the `f64` value and updated with a relaxed
+    /// compare-exchange loop rather than a plain `+=`.
+    pub fn par_accuflux(&mut self, weights: Option<&Array1<f64>>) -> Array1<f64> {
+        let size = self.idxs_ds.len();
+        let flux: Vec<AtomicU64> = (0..size)
+            .map(|idx| {
+                let w = weights.map_or(1.0, |w| w[idx]);
+                AtomicU64::new(w.to_bits())
+            })
+            .collect();
+
+        let ranks = self.rank().clone();
+        let topo = TopoOrder::build(&ranks);
+
+        // Highest rank (leaves) down to 0 (the outlet), so every receiver's
+        // own contribution is settled before it drains on.
+        for r in (0..=topo.max_rank).rev() {
+            topo.bucket(r).par_iter().for_each(|&idx| {
+                let idx_ds = self.idxs_ds[idx];
+                if idx_ds == idx || idx_ds >= size {
+                    return;
+                }
+                let add = f64::from_bits(flux[idx].load(AtomicOrdering::Relaxed));
+                let mut current = flux[idx_ds].load(AtomicOrdering::Relaxed);
+                loop {
+                    let updated = (f64::from_bits(current) + add).to_bits();
+                    match flux[idx_ds].compare_exchange_weak(
+                        current,
+                        updated,
+                        AtomicOrdering::Relaxed,
+                        AtomicOrdering::Relaxed,
+                    ) {
+                        Ok(_) => break,
+                        Err(observed) => current = observed,
                     }
                 }
+            });
+        }
+
+        Array1::from_vec(
+            flux.iter()
+                .map(|a| f64::from_bits(a.load(AtomicOrdering::Relaxed)))
+                .collect(),
+        )
+    }
+
+    /// Compute per-cell Strahler or Shreve stream order.
+    ///
+    /// Walks cells in decreasing `rank` order (leaves first, so every
+    /// upstream neighbor is already resolved before its receiver is
+    /// visited). A source cell (no upstream neighbors) gets order 1. At a
+    /// confluence, Shreve magnitude sums the incoming orders; Strahler
+    /// order takes the maximum incoming order and increments it only when
+    /// that maximum is shared by at least two contributing tributaries,
+    /// otherwise it simply carries the maximum forward.
+    pub fn stream_order(&mut self, mode: StreamOrder) -> Array2<i32> {
+        let shape = self.shape;
+        let size = self.idxs_ds.len();
+        let upstream = upstream_matrix(&self.idxs_ds);
+        let ranks = self.rank().clone();
+        let topo = TopoOrder::build(&ranks);
+
+        let mut order = Array1::zeros(size);
+
+        for idx in topo.iter_downstream() {
+            let mut incoming: Vec<i32> = Vec::new();
+            for k in 0..upstream.ncols() {
+                let up = upstream[[idx, k]];
+                if up == MV {
+                    break;
+                }
+                incoming.push(order[up as usize]);
             }
+
+            order[idx] = if incoming.is_empty() {
+                1
+            } else {
+                match mode {
+                    StreamOrder::Shreve => incoming.iter().sum(),
+                    StreamOrder::Strahler => {
+                        let max_order = *incoming.iter().max().unwrap();
+                        let n_max = incoming.iter().filter(|&&o| o == max_order).count();
+                        if n_max >= 2 { max_order + 1 } else { max_order }
+                    }
+                }
+            };
         }
-        
-        flux
+
+        Array2::from_shape_vec(shape, order.to_vec()).unwrap()
     }
-    
-    /// Get flow ranking with caching
-    /// Cached version to avoid redundant calculations
+
+    /// Fast non-cryptographic FNV-1a-style fingerprint (one xor-multiply
+    /// round per 64-bit value rather than per byte) of `idxs_ds` plus an
+    /// optional extra buffer (weights, uparea, ...), used as the `u64` half
+    /// of a cache key so e.g. `accuflux` called with different weights
+    /// doesn't collide with a previous call's cached result.
+    fn fingerprint_of(idxs_ds: &Array1<usize>, extra: Option<&[f64]>) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+        let mut hash = FNV_OFFSET;
+        for &v in idxs_ds.iter() {
+            hash ^= v as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+        if let Some(extra) = extra {
+            for &v in extra {
+                hash ^= v.to_bits();
+                hash = hash.wrapping_mul(FNV_PRIME);
+            }
+        }
+        hash
+    }
+
+    /// Fingerprint `idxs_ds` (and `extra`, if given) for the current raster.
+    fn fingerprint(&self, extra: Option<&[f64]>) -> u64 {
+        Self::fingerprint_of(&self.idxs_ds, extra)
+    }
+
+    /// Drop every cached derived array if `idxs_ds` has changed since
+    /// construction or the last `invalidate` call (e.g. after mutating it
+    /// in place); a no-op
+    /// otherwise. Call this after editing `idxs_ds` directly.
+    pub fn invalidate(&mut self) {
+        let current = self.fingerprint(None);
+        if current != self.idxs_fingerprint {
+            self.cache.clear();
+            self.idxs_fingerprint = current;
+        }
+    }
+
+    /// Get flow ranking, cached by a fingerprint of `idxs_ds`.
     pub fn rank(&mut self) -> &Array1<i32> {
-        if self.rank_cache.is_none() {
+        let key = (CacheKind::Rank, self.fingerprint(None));
+        if !self.cache.contains_key(&key) {
             let idxs_ds_i32 = self.get_idxs_ds_i32();
             let pits = Array1::from_elem(self.idxs_ds.len(), false);
             let (ranks, _) = rank(&idxs_ds_i32, &pits);
-            self.rank_cache = Some(ranks);
+            self.cache.insert(key, CachedData::Rank(ranks));
+        }
+        match self.cache.get(&key) {
+            Some(CachedData::Rank(r)) => r,
+            _ => unreachable!("rank cache entry must be CachedData::Rank"),
         }
-        self.rank_cache.as_ref().unwrap()
     }
-    
+
+    /// Downstream travel distance to each cell's pit, in whole cells -
+    /// cached by a fingerprint of `idxs_ds` like `rank`, from which it's
+    /// derived (`f64` rather than `i32` so it composes with distance-weighted
+    /// analyses that need a floating-point unit).
+    pub fn distance(&mut self) -> Array1<f64> {
+        let key = (CacheKind::Distance, self.fingerprint(None));
+        if let Some(CachedData::Distance(cached)) = self.cache.get(&key) {
+            return cached.clone();
+        }
+        let distances = self.rank().mapv(|r| r.max(0) as f64);
+        self.cache.insert(key, CachedData::Distance(distances.clone()));
+        distances
+    }
+
+    /// Index of the upstream neighbor with the largest `uparea`, cached by a
+    /// fingerprint of `idxs_ds` and `uparea` together.
+    pub fn upstream_main(
+        &mut self,
+        uparea: &Array1<crate::core::Float>,
+        upa_min: crate::core::Float,
+    ) -> Array1<isize> {
+        let uparea_f64: Vec<f64> = uparea.iter().copied().map(float_to_f64).collect();
+        let key = (CacheKind::UpstreamMain, self.fingerprint(Some(&uparea_f64)));
+        if let Some(CachedData::UpstreamMain(cached)) = self.cache.get(&key) {
+            return cached.clone();
+        }
+        let main = crate::core::main_upstream(&self.idxs_ds, uparea, upa_min);
+        self.cache.insert(key, CachedData::UpstreamMain(main.clone()));
+        main
+    }
+
     /// Get valid cell indices
     pub fn valid_indices(&self) -> Vec<usize> {
         let mut indices = Vec::new();
@@ -291,4 +874,283 @@ mod tests {
         assert_eq!(n_up[2], 0); // No upstream cells
         assert_eq!(n_up[3], 3); // Three upstream cells (0, 1, 2)
     }
+
+    #[test]
+    fn test_accuflux() {
+        // Same flow pattern as test_rank: cells 0, 1, 2 all flow into pit cell 3.
+        let d8 = array![[2u8, 4u8], [1u8, 0u8]];
+        let mut flwdir = FlwdirRaster::from_array(d8.view());
+
+        let flux = flwdir.accuflux(None);
+
+        // Leaves accumulate only their own weight (1 per cell).
+        assert_eq!(flux[[0, 0]], 1.0);
+        assert_eq!(flux[[0, 1]], 1.0);
+        assert_eq!(flux[[1, 0]], 1.0);
+        // The pit receives its own weight plus all three upstream cells.
+        assert_eq!(flux[[1, 1]], 4.0);
+    }
+
+    #[test]
+    fn test_par_accuflux() {
+        // Same flow pattern as test_accuflux: cells 0, 1, 2 all flow into
+        // pit cell 3, so the parallel and sequential results must agree.
+        let d8 = array![[2u8, 4u8], [1u8, 0u8]];
+        let mut flwdir = FlwdirRaster::from_array(d8.view());
+
+        let flux = flwdir.par_accuflux(None);
+
+        assert_eq!(flux[0], 1.0);
+        assert_eq!(flux[1], 1.0);
+        assert_eq!(flux[2], 1.0);
+        assert_eq!(flux[3], 4.0);
+    }
+
+    #[test]
+    fn test_cached_distance_and_upstream_main() {
+        // Same flow pattern as test_rank: cells 0, 1, 2 flow into pit cell 3.
+        let d8 = array![[2u8, 4u8], [1u8, 0u8]];
+        let mut flwdir = FlwdirRaster::from_array(d8.view());
+
+        let distance = flwdir.distance();
+        assert_eq!(distance[3], 0.0);
+        assert_eq!(distance[0], 1.0);
+
+        // Cell 1 has the larger uparea, so it should win as cell 3's main
+        // upstream neighbor; calling twice must hit the cache and agree.
+        let uparea: Array1<crate::core::Float> = array![1.0, 5.0, 2.0, 0.0];
+        let main_first = flwdir.upstream_main(&uparea, 0.0);
+        let main_second = flwdir.upstream_main(&uparea, 0.0);
+        assert_eq!(main_first[3], 1);
+        assert_eq!(main_second[3], 1);
+
+        // Mutating idxs_ds in place and invalidating must drop the stale
+        // cached rank/distance rather than serving them for the new network.
+        flwdir.idxs_ds[0] = 0; // cell 0 becomes its own pit
+        flwdir.invalidate();
+        let distance_after = flwdir.distance();
+        assert_eq!(distance_after[0], 0.0);
+    }
+
+    #[test]
+    fn test_stream_order() {
+        // Same flow pattern as test_rank: cells 0, 1, 2 are sources that
+        // all meet at pit cell 3 - three equal-order tributaries converge.
+        let d8 = array![[2u8, 4u8], [1u8, 0u8]];
+        let mut flwdir = FlwdirRaster::from_array(d8.view());
+
+        let shreve = flwdir.stream_order(StreamOrder::Shreve);
+        assert_eq!(shreve[[0, 0]], 1);
+        assert_eq!(shreve[[0, 1]], 1);
+        assert_eq!(shreve[[1, 0]], 1);
+        assert_eq!(shreve[[1, 1]], 3); // magnitude: sum of the three sources
+
+        let strahler = flwdir.stream_order(StreamOrder::Strahler);
+        assert_eq!(strahler[[0, 0]], 1);
+        assert_eq!(strahler[[0, 1]], 1);
+        assert_eq!(strahler[[1, 0]], 1);
+        assert_eq!(strahler[[1, 1]], 2); // 3 equal-order-1 tributaries meet
+    }
+
+    #[test]
+    fn test_breach_depressions() {
+        // Interior pit at (1, 1); its cheapest escape is the single step
+        // southwest to (2, 0), which sits on the raster boundary.
+        let elev = array![
+            [5.0, 5.0, 5.0],
+            [5.0, 0.0, 5.0],
+            [3.0, 5.0, 5.0],
+        ];
+        let flwdir = FlwdirRaster::from_dem_conditioned(
+            elev.view(),
+            f64::NAN,
+            ConditioningMode::Breach,
+            10,
+            100.0,
+        );
+
+        let pit_idx = 1 * 3 + 1;
+        let escape_idx = 2 * 3 + 0;
+        assert_ne!(flwdir.idxs_ds[pit_idx], pit_idx);
+        assert_eq!(flwdir.idxs_ds[pit_idx], escape_idx);
+    }
+
+    #[test]
+    fn test_breach_falls_back_to_fill_when_capped() {
+        // Same depression, but a near-zero cost cap makes the only breach
+        // route unaffordable; Hybrid must fill the pit instead of leaving
+        // it unresolved the way pure Breach would.
+        let elev = array![
+            [5.0, 5.0, 5.0],
+            [5.0, 0.0, 5.0],
+            [3.0, 5.0, 5.0],
+        ];
+        let flwdir = FlwdirRaster::from_dem_conditioned(
+            elev.view(),
+            f64::NAN,
+            ConditioningMode::Hybrid,
+            10,
+            0.0,
+        );
+
+        let pit_idx = 1 * 3 + 1;
+        assert_ne!(flwdir.idxs_ds[pit_idx], pit_idx);
+    }
+
+    #[test]
+    fn test_from_dem_fills_interior_pit() {
+        // A 5x5 bowl: elevation rises strictly with Chebyshev distance from
+        // the centre, and (to keep the fill order unambiguous) strictly
+        // within each distance ring too, so the centre is walled in on all
+        // sides by ever-higher rings with no ties anywhere. Nothing here
+        // drains on its own; priority-flood filling must raise the centre
+        // (and the ring around it) until a monotonic path to the boundary
+        // appears.
+        let elev = Array2::from_shape_fn((5, 5), |(row, col)| {
+            let cheby = (row as i32 - 2).abs().max((col as i32 - 2).abs());
+            (cheby * 100 - (row * 5 + col) as i32) as f64
+        });
+        let flwdir = FlwdirRaster::from_dem(elev.view(), f64::NAN);
+
+        let centre_idx = 2 * 5 + 2;
+        assert_ne!(
+            flwdir.idxs_ds[centre_idx], centre_idx,
+            "the interior pit must be filled, not left as its own outlet"
+        );
+
+        // Every remaining pit must sit on the raster boundary (5x5, so
+        // boundary cells are those with row or col in {0, 4}).
+        for &idx in flwdir.pit_indices.iter() {
+            let (row, col) = (idx / 5, idx % 5);
+            assert!(
+                row == 0 || row == 4 || col == 0 || col == 4,
+                "pit at ({row}, {col}) is not on the boundary"
+            );
+        }
+
+        // Following the flow chain from the centre must terminate at one of
+        // those boundary pits rather than looping or dead-ending early.
+        let mut idx = centre_idx;
+        for _ in 0..flwdir.idxs_ds.len() {
+            if flwdir.idxs_ds[idx] == idx {
+                break;
+            }
+            idx = flwdir.idxs_ds[idx];
+        }
+        assert_eq!(flwdir.idxs_ds[idx], idx, "flow chain never reached a pit");
+        let (row, col) = (idx / 5, idx % 5);
+        assert!(row == 0 || row == 4 || col == 0 || col == 4);
+    }
+
+    #[test]
+    fn test_from_dem_skips_nodata_hole() {
+        // A NoData cell at the centre must be left without a flow direction,
+        // must never be chosen as another cell's downstream neighbor, and
+        // must not be reported as a drainage outlet just because it
+        // self-loops the same way a genuine pit does.
+        let nodata = -9999.0;
+        let elev = array![
+            [5.0, 4.0, 3.0],
+            [6.0, nodata, 2.0],
+            [7.0, 8.0, 1.0],
+        ];
+        let flwdir = FlwdirRaster::from_dem(elev.view(), nodata);
+
+        let hole_idx = 1 * 3 + 1;
+        assert_eq!(
+            flwdir.idxs_ds[hole_idx], hole_idx,
+            "a NoData cell must not receive a flow direction"
+        );
+        for (idx, &ds) in flwdir.idxs_ds.iter().enumerate() {
+            if idx != hole_idx {
+                assert_ne!(ds, hole_idx, "no cell may flow into a NoData hole");
+            }
+        }
+        assert!(
+            !flwdir.pit_indices.iter().any(|&idx| idx == hole_idx),
+            "a NoData hole must not be reported as a pit"
+        );
+    }
+
+    #[test]
+    fn test_from_dem_resolves_flat_area() {
+        // A flat interior plateau (every non-boundary cell tied at the same
+        // elevation) walled in by a boundary that is everywhere higher
+        // except a single low escape cell. Without the priority-flood's
+        // epsilon-driven raising, every plateau cell not bordering the
+        // escape ties with its neighbors and has no strictly-lower one, so
+        // each would wrongly become its own pit; filling must carve a gradient across the
+        // whole plateau down to that one escape.
+        let escape = (2usize, 0usize);
+        let elev = Array2::from_shape_fn((5, 5), |(row, col)| {
+            if (row, col) == escape {
+                1.0
+            } else if row == 0 || row == 4 || col == 0 || col == 4 {
+                100.0 + (row * 5 + col) as f64 // distinct, always higher than the plateau
+            } else {
+                10.0 // flat interior plateau, deliberately tied
+            }
+        });
+        let flwdir = FlwdirRaster::from_dem(elev.view(), f64::NAN);
+
+        let escape_idx = escape.0 * 5 + escape.1;
+        assert_eq!(
+            flwdir.pit_indices.len(),
+            1,
+            "the only pit must be the escape cell, not a tie-broken plateau cell"
+        );
+        assert_eq!(flwdir.pit_indices[0], escape_idx);
+
+        // Every cell's flow chain must reach that one escape.
+        for start in 0..flwdir.idxs_ds.len() {
+            let mut idx = start;
+            for _ in 0..flwdir.idxs_ds.len() {
+                if flwdir.idxs_ds[idx] == idx {
+                    break;
+                }
+                idx = flwdir.idxs_ds[idx];
+            }
+            assert_eq!(idx, escape_idx, "cell {start} did not drain to the escape");
+        }
+    }
+
+    #[test]
+    fn test_breach_depressions_skips_nodata_pit() {
+        // Interior "pits" in the naive D8 include a real depression at
+        // (1, 1) and a NoData hole at (3, 3) (the flat 5.0 cells with no
+        // strictly-lower neighbor are naive pits too). The real depression
+        // must be breached; the NoData hole must be left alone rather than
+        // handed to `find_breach_path` as if it were a genuine pit to escape from.
+        let nodata = f64::NAN;
+        let elev = array![
+            [9.0, 9.0, 9.0, 9.0, 9.0],
+            [9.0, 0.0, 5.0, 5.0, 9.0],
+            [9.0, 5.0, 5.0, 5.0, 9.0],
+            [9.0, 5.0, 5.0, nodata, 9.0],
+            [9.0, 9.0, 9.0, 9.0, 9.0],
+        ];
+        let flwdir = FlwdirRaster::from_dem_conditioned(
+            elev.view(),
+            nodata,
+            ConditioningMode::Breach,
+            10,
+            100.0,
+        );
+
+        let pit_idx = 1 * 5 + 1;
+        let hole_idx = 3 * 5 + 3;
+        assert_ne!(
+            flwdir.idxs_ds[pit_idx], pit_idx,
+            "the real depression must still be breached"
+        );
+        assert_eq!(
+            flwdir.idxs_ds[hole_idx], hole_idx,
+            "a NoData hole must never be treated as a pit to breach"
+        );
+        for (idx, &ds) in flwdir.idxs_ds.iter().enumerate() {
+            if idx != hole_idx {
+                assert_ne!(ds, hole_idx, "no cell may flow into a NoData hole");
+            }
+        }
+    }
 } 
\ No newline at end of file